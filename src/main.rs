@@ -145,6 +145,7 @@ use structopt::StructOpt;
 
 const ARCHIVE_METADATA_JSON: &'static str = "deduposaur.archive_metadata.json";
 const PROCESS_METADATA_JSON: &'static str = "deduposaur.process_metadata.json";
+const IGNORE_FILE_NAME: &'static str = ".deduposaurignore";
 
 #[derive(Debug, StructOpt)]
 #[structopt(about)]
@@ -161,6 +162,27 @@ struct Opt {
     /// so it can record deleted files.
     #[structopt(long, parse(from_os_str))]
     process: Option<PathBuf>,
+    /// Glob pattern of paths to exclude from the archive and process dirs.
+    /// Matched the same way as patterns in a '.deduposaurignore' file.
+    /// May be repeated.
+    #[structopt(long = "ignore")]
+    ignore: Vec<String>,
+    /// Automatically accept every change instead of prompting.
+    #[structopt(long)]
+    assume_yes: bool,
+    /// Automatically reject every change instead of prompting.
+    #[structopt(long)]
+    assume_no: bool,
+    /// Write a JSON report of every detected event (new, changed, mtime_changed, renamed,
+    /// deleted, dupe, previously_deleted) to this path, instead of printing them to stdout.
+    #[structopt(long, parse(from_os_str))]
+    report: Option<PathBuf>,
+    /// Digest algorithm used to detect changed and duplicate files: 'sha256', 'blake3', or
+    /// 'sip128'. Defaults to the algorithm already recorded in the archive's metadata, or
+    /// 'sip128' for a brand-new archive. Switching algorithms re-hashes every file in the
+    /// archive and rewrites the metadata with the new digests.
+    #[structopt(long)]
+    digest: Option<DigestAlgorithm>,
 }
 
 pub fn read_json_file<T: for<'a> Deserialize<'a> + Default>(
@@ -187,24 +209,117 @@ pub fn read_json_file<T: for<'a> Deserialize<'a> + Default>(
 pub fn write_json_file(value: &impl Serialize, path: &Path) -> Result<(), String> {
     let writer = std::fs::File::create(path)
         .map_err(|e| format!("error writing {}: {}", path.to_string_lossy(), e))?;
-    serde_json::to_writer(writer, value)
+    serde_json::to_writer(&writer, value)
+        .map_err(|e| format!("error writing {}: {}", path.to_string_lossy(), e))?;
+    writer
+        .sync_all()
         .map_err(|e| format!("error writing {}: {}", path.to_string_lossy(), e))
 }
 
 #[serde_as]
 #[derive(Clone, Deserialize, Eq, Hash, PartialEq, Serialize)]
-pub struct FileDigest(#[serde_as(as = "serde_with::hex::Hex")] [u8; 32]);
+pub struct FileDigest(#[serde_as(as = "serde_with::hex::Hex")] Vec<u8>);
 impl Debug for FileDigest {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(f, "FileDigest({})", hex::encode(&self.0))
     }
 }
 
+/// Digest algorithm used to detect changed and duplicate files.  Recorded in the archive's
+/// metadata header so a later run with a different `--digest` can tell its stored digests are
+/// stale and re-hash, instead of reporting every file as changed.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DigestAlgorithm {
+    Sha256,
+    Blake3,
+    Sip128,
+}
+impl std::str::FromStr for DigestAlgorithm {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "sha256" => Ok(DigestAlgorithm::Sha256),
+            "blake3" => Ok(DigestAlgorithm::Blake3),
+            "sip128" => Ok(DigestAlgorithm::Sip128),
+            _ => Err(format!(
+                "unknown digest algorithm {:?}, expected 'sha256', 'blake3', or 'sip128'",
+                s
+            )),
+        }
+    }
+}
+impl std::fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str(match self {
+            DigestAlgorithm::Sha256 => "sha256",
+            DigestAlgorithm::Blake3 => "blake3",
+            DigestAlgorithm::Sip128 => "sip128",
+        })
+    }
+}
+/// The archive format predates the `digest_algorithm` header field, so metadata written before
+/// this field existed is assumed to hold SHA-256 digests, the only algorithm the tool ever used.
+fn legacy_digest_algorithm() -> DigestAlgorithm {
+    DigestAlgorithm::Sha256
+}
+
+/// Incremental hasher for whichever [`DigestAlgorithm`] is in effect, so the single-pass reads in
+/// [`read_file_digest`] and [`read_file_hashes`] don't need to special-case each algorithm.
+enum DigestHasher {
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Sip128(siphasher::sip128::SipHasher13),
+}
+impl DigestHasher {
+    fn new(algorithm: DigestAlgorithm) -> Self {
+        match algorithm {
+            DigestAlgorithm::Sha256 => DigestHasher::Sha256(sha2::Sha256::new()),
+            DigestAlgorithm::Blake3 => DigestHasher::Blake3(Box::new(blake3::Hasher::new())),
+            DigestAlgorithm::Sip128 => DigestHasher::Sip128(siphasher::sip128::SipHasher13::new()),
+        }
+    }
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            DigestHasher::Sha256(hasher) => hasher.update(bytes),
+            DigestHasher::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+            DigestHasher::Sip128(hasher) => std::hash::Hasher::write(hasher, bytes),
+        }
+    }
+    fn finish(self) -> FileDigest {
+        use siphasher::sip128::Hasher128;
+        match self {
+            DigestHasher::Sha256(hasher) => FileDigest(hasher.finalize().to_vec()),
+            DigestHasher::Blake3(hasher) => FileDigest(hasher.finalize().as_bytes().to_vec()),
+            DigestHasher::Sip128(hasher) => {
+                let hash128 = hasher.finish128();
+                FileDigest([hash128.h1.to_le_bytes(), hash128.h2.to_le_bytes()].concat())
+            }
+        }
+    }
+}
+
+/// Number of leading bytes hashed to build a [`PartialKey`].
+/// Files shorter than this just hash their whole contents.
+const PARTIAL_DIGEST_LEN: usize = 4096;
+
+/// Cheap fingerprint `(file_size, digest_of_first_4096_bytes)` used to bucket files before
+/// paying for a full-file digest.  Two files with different `PartialKey`s are guaranteed to
+/// have different contents, so a mismatch here lets us skip reading the rest of the file.
+type PartialKey = (u64, FileDigest);
+
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct FileRecord {
     path: String,
     mtime: i64,
-    digest: FileDigest,
+    size: u64,
+    partial_digest: FileDigest,
+    /// Full-file digest.  Absent for files that were recorded as new without ever being read in
+    /// full, since their `partial_digest` didn't collide with anything that needed confirming.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    digest: Option<FileDigest>,
     #[serde(skip)]
     processed: bool,
 }
@@ -216,26 +331,85 @@ impl FileRecord {
             .to_string_lossy()
             .to_string()
     }
+    fn partial_key(&self) -> PartialKey {
+        (self.size, self.partial_digest.clone())
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ArchiveMetadata {
+    #[serde(default = "legacy_digest_algorithm")]
+    digest_algorithm: DigestAlgorithm,
     expected: Vec<RefCell<FileRecord>>,
     deleted: Vec<FileRecord>,
 }
 impl Default for ArchiveMetadata {
     fn default() -> Self {
         ArchiveMetadata {
+            digest_algorithm: DigestAlgorithm::Sip128,
             expected: Vec::new(),
             deleted: Vec::new(),
         }
     }
 }
 
-fn read_file_digest(path: &Path) -> Result<FileDigest, String> {
+/// Like [`ArchiveMetadata`], records which [`DigestAlgorithm`] its `new_files` digests were
+/// computed with, so a `--digest` change doesn't make every still-pending process-dir file look
+/// deleted just because its stored [`PartialKey`] no longer matches.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProcessMetadata {
+    #[serde(default = "legacy_digest_algorithm")]
+    digest_algorithm: DigestAlgorithm,
+    new_files: Vec<FileRecord>,
+}
+impl Default for ProcessMetadata {
+    fn default() -> Self {
+        ProcessMetadata {
+            digest_algorithm: DigestAlgorithm::Sip128,
+            new_files: Vec::new(),
+        }
+    }
+}
+
+/// One detected file-state change, recorded for `--report=PATH` instead of printed to stdout.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    New {
+        path: String,
+        mtime: i64,
+        digest: Option<FileDigest>,
+    },
+    Changed {
+        path: String,
+        old_digest: Option<FileDigest>,
+        new_digest: Option<FileDigest>,
+    },
+    MtimeChanged {
+        path: String,
+        old_mtime: i64,
+        new_mtime: i64,
+    },
+    Renamed {
+        old_path: String,
+        new_path: String,
+    },
+    Deleted {
+        path: String,
+    },
+    Dupe {
+        path: String,
+        existing_path: String,
+    },
+    PreviouslyDeleted {
+        path: String,
+    },
+}
+
+fn read_file_digest(path: &Path, algorithm: DigestAlgorithm) -> Result<FileDigest, String> {
     let mut reader = std::fs::File::open(path)
         .map_err(|e| format!("error reading {}: {}", path.to_string_lossy(), e))?;
-    let mut hasher = sha2::Sha256::new();
+    let mut hasher = DigestHasher::new(algorithm);
     let mut buffer = [0_u8; 1024 * 1024];
     loop {
         let num_bytes_read = reader
@@ -246,10 +420,117 @@ fn read_file_digest(path: &Path) -> Result<FileDigest, String> {
         }
         hasher.update(&buffer[..num_bytes_read]);
     }
-    Ok(FileDigest(hasher.finalize().into()))
+    Ok(hasher.finish())
+}
+
+/// Reads a file once, computing its size, [`PartialKey`] digest, and full digest together so
+/// callers that need all three (like the archive walk) never pay for a second pass.
+fn read_file_hashes(
+    path: &Path,
+    algorithm: DigestAlgorithm,
+) -> Result<(u64, FileDigest, FileDigest), String> {
+    let mut reader = std::fs::File::open(path)
+        .map_err(|e| format!("error reading {}: {}", path.to_string_lossy(), e))?;
+    let mut full_hasher = DigestHasher::new(algorithm);
+    let mut partial_hasher = DigestHasher::new(algorithm);
+    let mut size = 0_u64;
+    let mut buffer = [0_u8; 1024 * 1024];
+    loop {
+        let num_bytes_read = reader
+            .read(&mut buffer)
+            .map_err(|e| format!("error reading {}: {}", path.to_string_lossy(), e))?;
+        if num_bytes_read == 0 {
+            break;
+        }
+        full_hasher.update(&buffer[..num_bytes_read]);
+        let partial_remaining = PARTIAL_DIGEST_LEN.saturating_sub(size as usize);
+        if partial_remaining > 0 {
+            partial_hasher.update(&buffer[..partial_remaining.min(num_bytes_read)]);
+        }
+        size += num_bytes_read as u64;
+    }
+    Ok((size, partial_hasher.finish(), full_hasher.finish()))
 }
 
-fn walk_dir(path: &Path, records: &mut Vec<FileRecord>) -> Result<(), String> {
+/// Computes just a file's [`PartialKey`]: its size (from metadata, not a read) and the digest of
+/// its first [`PARTIAL_DIGEST_LEN`] bytes.  Used to cheaply rule out matches before falling back
+/// to [`read_file_digest`] for a whole multi-gigabyte file.
+fn read_partial_key(path: &Path, algorithm: DigestAlgorithm) -> Result<PartialKey, String> {
+    let mut reader = std::fs::File::open(path)
+        .map_err(|e| format!("error reading {}: {}", path.to_string_lossy(), e))?;
+    let size = reader
+        .metadata()
+        .map_err(|e| format!("error reading {}: {}", path.to_string_lossy(), e))?
+        .len();
+    let mut buffer = [0_u8; PARTIAL_DIGEST_LEN];
+    let mut num_read = 0_usize;
+    while num_read < buffer.len() {
+        let n = reader
+            .read(&mut buffer[num_read..])
+            .map_err(|e| format!("error reading {}: {}", path.to_string_lossy(), e))?;
+        if n == 0 {
+            break;
+        }
+        num_read += n;
+    }
+    let mut hasher = DigestHasher::new(algorithm);
+    hasher.update(&buffer[..num_read]);
+    Ok((size, hasher.finish()))
+}
+
+/// A pattern from a '.deduposaurignore' file or an `--ignore` flag.
+/// Patterns without a '/' match the file name at any depth, like a gitignore pattern.
+/// Patterns with a '/' match the path relative to the dir being walked.
+struct IgnorePattern {
+    glob: glob::Pattern,
+    anchored: bool,
+}
+
+fn parse_ignore_patterns(contents: &str) -> Vec<IgnorePattern> {
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| match glob::Pattern::new(line) {
+            Ok(glob) => Some(IgnorePattern {
+                glob,
+                anchored: line.contains('/'),
+            }),
+            Err(e) => {
+                println!("WARNING ignoring invalid pattern {:?}: {}", line, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Reads and parses the dir's '.deduposaurignore' file, if any, and appends patterns from
+/// `--ignore` flags.
+fn load_ignore_patterns(dir: &Path, extra_patterns: &[String]) -> Result<Vec<IgnorePattern>, String> {
+    let mut patterns = match read_file(dir.join(IGNORE_FILE_NAME))? {
+        Some(bytes) => parse_ignore_patterns(&String::from_utf8_lossy(&bytes)),
+        None => Vec::new(),
+    };
+    patterns.extend(parse_ignore_patterns(&extra_patterns.join("\n")));
+    Ok(patterns)
+}
+
+fn is_ignored(patterns: &[IgnorePattern], rel_path: &str, file_name: &str) -> bool {
+    patterns.iter().any(|pattern| {
+        if pattern.anchored {
+            pattern.glob.matches(rel_path)
+        } else {
+            pattern.glob.matches(file_name)
+        }
+    })
+}
+
+fn walk_dir(
+    path: &Path,
+    ignore_patterns: &[IgnorePattern],
+    algorithm: DigestAlgorithm,
+    records: &mut Vec<FileRecord>,
+) -> Result<(), String> {
     let mut dirs: Vec<PathBuf> = vec![path.to_path_buf()];
     while let Some(dir) = dirs.pop() {
         for entry_result in dir
@@ -258,11 +539,17 @@ fn walk_dir(path: &Path, records: &mut Vec<FileRecord>) -> Result<(), String> {
         {
             let entry = entry_result
                 .map_err(|e| format!("error reading dir {}: {}", dir.to_string_lossy(), e))?;
-            if entry
-                .file_name()
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if file_name.starts_with(ARCHIVE_METADATA_JSON) || file_name == IGNORE_FILE_NAME {
+                continue;
+            }
+            let rel_path = entry
+                .path()
+                .strip_prefix(path)
+                .unwrap()
                 .to_string_lossy()
-                .starts_with(ARCHIVE_METADATA_JSON)
-            {
+                .to_string();
+            if is_ignored(ignore_patterns, &rel_path, &file_name) {
                 continue;
             }
             let metadata = entry
@@ -271,15 +558,13 @@ fn walk_dir(path: &Path, records: &mut Vec<FileRecord>) -> Result<(), String> {
             if metadata.is_dir() {
                 dirs.push(entry.path());
             } else if metadata.is_file() {
+                let (size, partial_digest, digest) = read_file_hashes(&entry.path(), algorithm)?;
                 records.push(FileRecord {
-                    path: entry
-                        .path()
-                        .strip_prefix(path)
-                        .unwrap()
-                        .to_string_lossy()
-                        .to_string(),
+                    path: rel_path,
                     mtime: metadata.st_mtime(),
-                    digest: read_file_digest(&entry.path())?,
+                    size,
+                    partial_digest,
+                    digest: Some(digest),
                     processed: false,
                 });
             } else {
@@ -293,6 +578,60 @@ fn walk_dir(path: &Path, records: &mut Vec<FileRecord>) -> Result<(), String> {
     Ok(())
 }
 
+/// A file found while walking the process dir, before we've decided whether its contents need a
+/// full read: just enough to compute a [`PartialKey`] on demand.
+struct CandidateFile {
+    path: String,
+    mtime: i64,
+}
+
+fn walk_dir_candidates(
+    path: &Path,
+    ignore_patterns: &[IgnorePattern],
+) -> Result<Vec<CandidateFile>, String> {
+    let mut records = Vec::new();
+    let mut dirs: Vec<PathBuf> = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry_result in dir
+            .read_dir()
+            .map_err(|e| format!("error reading dir {}: {}", dir.to_string_lossy(), e))?
+        {
+            let entry = entry_result
+                .map_err(|e| format!("error reading dir {}: {}", dir.to_string_lossy(), e))?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if file_name.starts_with(ARCHIVE_METADATA_JSON) || file_name == IGNORE_FILE_NAME {
+                continue;
+            }
+            let rel_path = entry
+                .path()
+                .strip_prefix(path)
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            if is_ignored(ignore_patterns, &rel_path, &file_name) {
+                continue;
+            }
+            let metadata = entry
+                .metadata()
+                .map_err(|e| format!("error reading {}: {}", entry.path().to_string_lossy(), e))?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else if metadata.is_file() {
+                records.push(CandidateFile {
+                    path: rel_path,
+                    mtime: metadata.st_mtime(),
+                });
+            } else {
+                println!(
+                    "WARNING Ignoring non-file {}",
+                    entry.path().to_string_lossy()
+                );
+            }
+        }
+    }
+    Ok(records)
+}
+
 pub fn read_byte_from_stdin() -> Result<u8, String> {
     std::io::stdin()
         .bytes()
@@ -301,13 +640,33 @@ pub fn read_byte_from_stdin() -> Result<u8, String> {
         .map_err(|e| format!("error reading stdin: {}", e))
 }
 
+/// How to answer prompts that would normally wait on stdin.
+#[derive(Clone, Copy, PartialEq)]
+enum Assume {
+    Ask,
+    Yes,
+    No,
+}
+
+/// Settings shared by every function that would otherwise print a WARNING and prompt: how to
+/// answer, and whether to stay quiet because `--report` is writing the same information as JSON.
+struct PromptSettings {
+    assume: Assume,
+    quiet: bool,
+}
+
 #[derive(PartialEq)]
 enum PromptResponse {
     Yes,
     No,
 }
 impl PromptResponse {
-    pub fn prompt_and_read() -> Result<PromptResponse, String> {
+    pub fn prompt_and_read(settings: &PromptSettings) -> Result<PromptResponse, String> {
+        match settings.assume {
+            Assume::Yes => return Ok(PromptResponse::Yes),
+            Assume::No => return Ok(PromptResponse::No),
+            Assume::Ask => {}
+        }
         loop {
             println!("Accept change? (y/n) ");
             match read_byte_from_stdin()? {
@@ -325,7 +684,12 @@ enum PromptWithRevertResponse {
     Revert,
 }
 impl PromptWithRevertResponse {
-    pub fn prompt_and_read() -> Result<PromptWithRevertResponse, String> {
+    pub fn prompt_and_read(settings: &PromptSettings) -> Result<PromptWithRevertResponse, String> {
+        match settings.assume {
+            Assume::Yes => return Ok(PromptWithRevertResponse::Yes),
+            Assume::No => return Ok(PromptWithRevertResponse::No),
+            Assume::Ask => {}
+        }
         loop {
             println!("Accept (y/n) or revert (r)? ");
             match read_byte_from_stdin()? {
@@ -348,6 +712,9 @@ fn get_opt() -> Opt {
             panic!("expected path, got empty string '--process='");
         }
     }
+    if opt.assume_yes && opt.assume_no {
+        panic!("expected only one of '--assume-yes' and '--assume-no'");
+    }
     opt
 }
 
@@ -355,6 +722,8 @@ fn check_for_existing_and_changed_files(
     expected_records: &Vec<RefCell<FileRecord>>,
     actual_records: &mut Vec<FileRecord>,
     archive_path: &Path,
+    settings: &PromptSettings,
+    events: &mut Vec<Event>,
 ) -> Result<bool, String> {
     let mut all_ok = true;
     let index: HashMap<String, &RefCell<FileRecord>> = HashMap::from_iter(
@@ -368,21 +737,37 @@ fn check_for_existing_and_changed_files(
             let mut expected = expected_cell.borrow_mut();
             expected.processed = true;
             if expected.digest != actual.digest {
-                println!("WARNING {} is changed", actual.path);
-                if PromptResponse::prompt_and_read()? == PromptResponse::Yes {
-                    expected.digest.0 = actual.digest.0;
+                if !settings.quiet {
+                    println!("WARNING {} is changed", actual.path);
+                }
+                events.push(Event::Changed {
+                    path: actual.path.clone(),
+                    old_digest: expected.digest.clone(),
+                    new_digest: actual.digest.clone(),
+                });
+                if PromptResponse::prompt_and_read(settings)? == PromptResponse::Yes {
+                    expected.size = actual.size;
+                    expected.partial_digest = actual.partial_digest.clone();
+                    expected.digest = actual.digest.clone();
                     expected.mtime = actual.mtime;
                 } else {
                     all_ok = false;
                 }
             } else if expected.mtime != actual.mtime {
-                println!(
-                    "WARNING {} mtime changed {} -> {}",
-                    actual.path,
-                    chrono::Local.timestamp(expected.mtime, 0).to_rfc3339(),
-                    chrono::Local.timestamp(actual.mtime, 0).to_rfc3339(),
-                );
-                match PromptWithRevertResponse::prompt_and_read()? {
+                if !settings.quiet {
+                    println!(
+                        "WARNING {} mtime changed {} -> {}",
+                        actual.path,
+                        chrono::Local.timestamp(expected.mtime, 0).to_rfc3339(),
+                        chrono::Local.timestamp(actual.mtime, 0).to_rfc3339(),
+                    );
+                }
+                events.push(Event::MtimeChanged {
+                    path: actual.path.clone(),
+                    old_mtime: expected.mtime,
+                    new_mtime: actual.mtime,
+                });
+                match PromptWithRevertResponse::prompt_and_read(settings)? {
                     PromptWithRevertResponse::Yes => {
                         expected.mtime = actual.mtime;
                     }
@@ -404,9 +789,11 @@ fn check_for_existing_and_changed_files(
 fn check_for_renamed_files(
     expected_records: &Vec<RefCell<FileRecord>>,
     actual_records: &mut Vec<FileRecord>,
+    settings: &PromptSettings,
+    events: &mut Vec<Event>,
 ) -> Result<bool, String> {
     let mut all_ok = true;
-    let index: HashMap<(i64, FileDigest), &RefCell<FileRecord>> = HashMap::from_iter(
+    let index: HashMap<(i64, Option<FileDigest>), &RefCell<FileRecord>> = HashMap::from_iter(
         expected_records
             .iter()
             .filter(|elem| !elem.borrow().processed)
@@ -418,8 +805,14 @@ fn check_for_renamed_files(
             let mut expected = expected_cell.borrow_mut();
             expected.processed = true;
             if expected.path != actual.path {
-                println!("WARNING {} is renamed to {}", expected.path, actual.path);
-                if PromptResponse::prompt_and_read()? == PromptResponse::Yes {
+                if !settings.quiet {
+                    println!("WARNING {} is renamed to {}", expected.path, actual.path);
+                }
+                events.push(Event::Renamed {
+                    old_path: expected.path.clone(),
+                    new_path: actual.path.clone(),
+                });
+                if PromptResponse::prompt_and_read(settings)? == PromptResponse::Yes {
                     expected.path = actual.path.clone();
                 } else {
                     all_ok = false;
@@ -430,7 +823,11 @@ fn check_for_renamed_files(
     Ok(all_ok)
 }
 
-fn check_for_deleted_files(archive_metadata: &mut ArchiveMetadata) -> Result<bool, String> {
+fn check_for_deleted_files(
+    archive_metadata: &mut ArchiveMetadata,
+    settings: &PromptSettings,
+    events: &mut Vec<Event>,
+) -> Result<bool, String> {
     let mut all_ok = true;
     // Treat all remaining unprocessed expected files as deleted.
     let expected_copies: Vec<FileRecord> = archive_metadata
@@ -440,8 +837,13 @@ fn check_for_deleted_files(archive_metadata: &mut ArchiveMetadata) -> Result<boo
         .map(|elem| elem.borrow().clone())
         .collect();
     for expected_copy in expected_copies {
-        println!("WARNING {} is deleted", expected_copy.path);
-        if PromptResponse::prompt_and_read()? == PromptResponse::Yes {
+        if !settings.quiet {
+            println!("WARNING {} is deleted", expected_copy.path);
+        }
+        events.push(Event::Deleted {
+            path: expected_copy.path.clone(),
+        });
+        if PromptResponse::prompt_and_read(settings)? == PromptResponse::Yes {
             archive_metadata
                 .expected
                 .retain(|elem| *elem.borrow() != expected_copy);
@@ -456,10 +858,16 @@ fn check_for_deleted_files(archive_metadata: &mut ArchiveMetadata) -> Result<boo
 fn check_for_new_files(
     archive_metadata: &mut ArchiveMetadata,
     actual_records: &mut Vec<FileRecord>,
+    events: &mut Vec<Event>,
 ) {
     // Treat all remaining unprocessed actual files as new.
     for actual in actual_records.iter_mut().filter(|elem| !elem.processed) {
         actual.processed = true;
+        events.push(Event::New {
+            path: actual.path.clone(),
+            mtime: actual.mtime,
+            digest: actual.digest.clone(),
+        });
         archive_metadata.expected.push(RefCell::new(actual.clone()));
         archive_metadata.deleted.retain(|elem| {
             (elem.mtime, &elem.path, &elem.digest) != (actual.mtime, &actual.path, &actual.digest)
@@ -498,6 +906,72 @@ fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<(), String> {
     })
 }
 
+/// Fsyncs a dir so a rename of one of its entries is durable across a crash, on filesystems
+/// that support it.
+fn fsync_dir(dir: &Path) -> Result<(), String> {
+    std::fs::File::open(dir)
+        .and_then(|f| f.sync_all())
+        .map_err(|e| format!("error syncing {}: {}", dir.to_string_lossy(), e))
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new("."))
+}
+
+/// Writes `value` to a '.tmp' file next to `path`, fsyncs it, then atomically renames it over
+/// `path` and fsyncs the containing dir. There is no window where `path` holds invalid JSON.
+fn write_json_file_atomically(value: &impl Serialize, path: &Path) -> Result<(), String> {
+    let temp_path = {
+        let mut s = path.as_os_str().to_os_string();
+        s.push(".tmp");
+        PathBuf::from(s)
+    };
+    write_json_file(value, &temp_path)?;
+    rename(&temp_path, path)?;
+    fsync_dir(parent_dir(path))
+}
+
+/// Re-hashes every `expected` file with `new_algorithm` and updates the header, so a `--digest`
+/// switch rewrites the metadata instead of every file coming up "changed" against stale digests.
+/// A record whose file can't be read (deleted, renamed) is left untouched; the existing
+/// deleted/renamed/changed detection in [`main`] gets a chance to handle it under the old digest
+/// instead of the whole run aborting. `deleted` records can't be re-hashed since their files are
+/// gone; they just stop matching future `previously_deleted` lookups under the new algorithm.
+fn rehash_archive_metadata(
+    archive_metadata: &mut ArchiveMetadata,
+    archive_dir: &Path,
+    new_algorithm: DigestAlgorithm,
+) -> Result<(), String> {
+    for record_cell in &archive_metadata.expected {
+        let mut record = record_cell.borrow_mut();
+        if let Ok((size, partial_digest, digest)) =
+            read_file_hashes(&archive_dir.join(&record.path), new_algorithm)
+        {
+            record.size = size;
+            record.partial_digest = partial_digest;
+            record.digest = Some(digest);
+        }
+    }
+    archive_metadata.digest_algorithm = new_algorithm;
+    Ok(())
+}
+
+/// Re-hashes every `new_files` record still present in `process_dir` with `new_algorithm`, so a
+/// `--digest` switch doesn't make still-pending process-dir files look deleted just because their
+/// stored [`PartialKey`] no longer matches one computed with the new algorithm. A record whose
+/// file is actually gone is left alone; the matching in [`process_files`] will report it deleted.
+fn rehash_process_metadata(new_files: &mut [FileRecord], process_dir: &Path, new_algorithm: DigestAlgorithm) {
+    for record in new_files.iter_mut() {
+        if let Ok((size, partial_digest, digest)) =
+            read_file_hashes(&process_dir.join(&record.path), new_algorithm)
+        {
+            record.size = size;
+            record.partial_digest = partial_digest;
+            record.digest = record.digest.is_some().then_some(digest);
+        }
+    }
+}
+
 fn write_archive_metadata(
     archive_metadata_path: &PathBuf,
     archive_metadata: &ArchiveMetadata,
@@ -528,7 +1002,8 @@ fn write_archive_metadata(
         PathBuf::from(s)
     };
     rename(&archive_metadata_path, &backup_archive_metadata_path)?;
-    rename(&temp_archive_metadata_path, &archive_metadata_path)
+    rename(&temp_archive_metadata_path, &archive_metadata_path)?;
+    fsync_dir(parent_dir(archive_metadata_path))
 }
 
 fn rename_with_prefix(
@@ -536,6 +1011,7 @@ fn rename_with_prefix(
     path: &str,
     suffix: &'static str,
     remark: Option<&str>,
+    quiet: bool,
 ) -> Result<(), String> {
     let path_buf = PathBuf::from(&path);
     let new_name = suffix.to_string() + &path_buf.file_name().unwrap().to_string_lossy();
@@ -545,10 +1021,12 @@ fn rename_with_prefix(
         PathBuf::from(&new_name)
     };
     rename(dir.join(path), dir.join(&new_path))?;
-    if let Some(remark) = remark {
-        println!("Renamed {} - {}", new_path.to_string_lossy(), remark);
-    } else {
-        println!("Renamed {}", new_path.to_string_lossy());
+    if !quiet {
+        if let Some(remark) = remark {
+            println!("Renamed {} - {}", new_path.to_string_lossy(), remark);
+        } else {
+            println!("Renamed {}", new_path.to_string_lossy());
+        }
     }
     Ok(())
 }
@@ -557,114 +1035,253 @@ fn process_files(
     archive_metadata: &mut ArchiveMetadata,
     archive_dir: &Path,
     process_dir: &Path,
+    ignore_patterns: &[IgnorePattern],
+    algorithm: DigestAlgorithm,
+    settings: &PromptSettings,
+    events: &mut Vec<Event>,
 ) -> Result<(), String> {
-    let mut records: Vec<FileRecord> = Vec::new();
-    walk_dir(process_dir.as_ref(), &mut records)?;
-    records.retain(|record| {
-        let file_name = record.file_name();
+    let mut candidates = walk_dir_candidates(process_dir.as_ref(), ignore_patterns)?;
+    candidates.retain(|candidate| {
+        let file_name = PathBuf::from(&candidate.path)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
         file_name != PROCESS_METADATA_JSON
             && !file_name.starts_with("DUPE.")
             && !file_name.starts_with("DELETED.")
             && !file_name.starts_with("CHANGED.")
             && !file_name.starts_with("METADATA.")
     });
+    // Phase 1: read just the cheap PartialKey of every candidate up front. A mismatched
+    // PartialKey rules out a dupe/changed/deleted match without reading the rest of the file.
+    let candidates: Vec<(CandidateFile, PartialKey)> = candidates
+        .into_iter()
+        .map(|candidate| {
+            let partial_key = read_partial_key(&process_dir.join(&candidate.path), algorithm)?;
+            Ok((candidate, partial_key))
+        })
+        .collect::<Result<_, String>>()?;
+
     let process_metadata_json_path = process_dir.join(PROCESS_METADATA_JSON);
-    let mut new_files: Vec<FileRecord> = read_json_file(&process_metadata_json_path, true)?;
+    let process_metadata: ProcessMetadata = read_json_file(&process_metadata_json_path, true)?;
+    let mut new_files = process_metadata.new_files;
+    if process_metadata.digest_algorithm != algorithm {
+        rehash_process_metadata(&mut new_files, process_dir, algorithm);
+    }
     {
-        let process_digests: HashSet<FileDigest, RandomState> =
-            HashSet::from_iter(records.iter().map(|record| record.digest.clone()));
+        let candidate_partial_keys: HashSet<PartialKey, RandomState> =
+            HashSet::from_iter(candidates.iter().map(|(_, partial_key)| partial_key.clone()));
         new_files.retain(|new_file| {
-            if process_digests.contains(&new_file.digest) {
+            if candidate_partial_keys.contains(&new_file.partial_key()) {
                 // File still exists in process dir.
                 true
             } else {
                 // File was deleted from process dir.
-                println!("{} was deleted", new_file.path);
+                if !settings.quiet {
+                    println!("{} was deleted", new_file.path);
+                }
                 archive_metadata.deleted.push(new_file.clone());
                 false
             }
         });
     }
-    let existing_paths: HashMap<(i64, FileDigest), String> =
-        HashMap::from_iter(archive_metadata.expected.iter().map(|record_cell| {
-            (
-                (
-                    record_cell.borrow().mtime,
-                    record_cell.borrow().digest.clone(),
-                ),
-                record_cell.borrow().path.clone(),
-            )
-        }));
-    let deleted_digests: HashSet<FileDigest, RandomState> = HashSet::from_iter(
+    // These two sets are free: every expected/deleted entry's PartialKey already lives in the
+    // archive metadata, so matching against them never touches the archive's files.
+    let expected_partial_keys: HashSet<PartialKey, RandomState> = HashSet::from_iter(
         archive_metadata
-            .deleted
+            .expected
             .iter()
-            .map(|record| record.digest.clone()),
+            .map(|record_cell| record_cell.borrow().partial_key()),
     );
+    // Grouped by PartialKey rather than a flat digest set because a deleted record pushed in
+    // from `new_files` may never have had its full digest computed (see below); such a record can
+    // only be matched back by PartialKey.
+    let deleted_by_partial_key: HashMap<PartialKey, Vec<&FileRecord>> = {
+        let mut map: HashMap<PartialKey, Vec<&FileRecord>> = HashMap::new();
+        for record in &archive_metadata.deleted {
+            map.entry(record.partial_key()).or_default().push(record);
+        }
+        map
+    };
+    let existing_paths: HashMap<(i64, FileDigest), String> =
+        HashMap::from_iter(archive_metadata.expected.iter().filter_map(|record_cell| {
+            let record = record_cell.borrow();
+            record
+                .digest
+                .clone()
+                .map(|digest| ((record.mtime, digest), record.path.clone()))
+        }));
     let index: HashMap<String, &RefCell<FileRecord>> = HashMap::from_iter(
         archive_metadata
             .expected
             .iter()
             .map(|r| (r.borrow().path.clone(), r)),
     );
-    for record in records {
+    for (candidate, partial_key) in candidates {
+        // Phase 2: only fall back to a full-file read when the PartialKey might collide with a
+        // known expected/deleted file, or the path itself is already tracked.
+        let might_match = expected_partial_keys.contains(&partial_key)
+            || deleted_by_partial_key.contains_key(&partial_key)
+            || index.contains_key(&candidate.path);
+        if !might_match {
+            // Unique size/first-block: can't be a dupe, changed, or previously-deleted file.
+            events.push(Event::New {
+                path: candidate.path.clone(),
+                mtime: candidate.mtime,
+                digest: None,
+            });
+            new_files.push(FileRecord {
+                path: candidate.path,
+                mtime: candidate.mtime,
+                size: partial_key.0,
+                partial_digest: partial_key.1,
+                digest: None,
+                processed: false,
+            });
+            continue;
+        }
+        let digest = read_file_digest(&process_dir.join(&candidate.path), algorithm)?;
         // Rename dupes.
-        if let Some(existing_path) = existing_paths.get(&(record.mtime, record.digest.clone())) {
+        if let Some(existing_path) = existing_paths.get(&(candidate.mtime, digest.clone())) {
+            events.push(Event::Dupe {
+                path: candidate.path.clone(),
+                existing_path: existing_path.clone(),
+            });
             rename_with_prefix(
                 process_dir,
-                &record.path,
+                &candidate.path,
                 "DUPE.",
                 Some(&archive_dir.join(existing_path).to_string_lossy()),
+                settings.quiet,
             )?;
             continue;
         }
-        // Rename previously deleted.
-        if deleted_digests.contains(&record.digest) {
-            rename_with_prefix(process_dir, &record.path, "DELETED.", None)?;
-            continue;
+        // Rename previously deleted. A deleted record pushed in from `new_files` may have no
+        // full digest of its own (it was unique by PartialKey and never read in full before
+        // being removed), so such a record is matched on PartialKey alone instead of digest.
+        if let Some(matches) = deleted_by_partial_key.get(&partial_key) {
+            if matches
+                .iter()
+                .any(|record| record.digest.as_ref().is_none_or(|d| d == &digest))
+            {
+                events.push(Event::PreviouslyDeleted {
+                    path: candidate.path.clone(),
+                });
+                rename_with_prefix(process_dir, &candidate.path, "DELETED.", None, settings.quiet)?;
+                continue;
+            }
         }
-        if let Some(expected_cell) = index.get(&record.path) {
+        if let Some(expected_cell) = index.get(&candidate.path) {
+            let expected = expected_cell.borrow();
             // Rename changed.
-            if expected_cell.borrow().digest != record.digest {
-                rename_with_prefix(process_dir, &record.path, "CHANGED.", None)?;
+            if expected.digest != Some(digest.clone()) {
+                events.push(Event::Changed {
+                    path: candidate.path.clone(),
+                    old_digest: expected.digest.clone(),
+                    new_digest: Some(digest),
+                });
+                drop(expected);
+                rename_with_prefix(process_dir, &candidate.path, "CHANGED.", None, settings.quiet)?;
                 continue;
             }
             // Rename metadata changed.
-            if expected_cell.borrow().mtime != record.mtime {
-                rename_with_prefix(process_dir, &record.path, "METADATA.", None)?;
+            if expected.mtime != candidate.mtime {
+                events.push(Event::MtimeChanged {
+                    path: candidate.path.clone(),
+                    old_mtime: expected.mtime,
+                    new_mtime: candidate.mtime,
+                });
+                drop(expected);
+                rename_with_prefix(process_dir, &candidate.path, "METADATA.", None, settings.quiet)?;
                 continue;
             }
         }
         // Remember new files.
-        new_files.push(record);
+        events.push(Event::New {
+            path: candidate.path.clone(),
+            mtime: candidate.mtime,
+            digest: Some(digest.clone()),
+        });
+        new_files.push(FileRecord {
+            path: candidate.path,
+            mtime: candidate.mtime,
+            size: partial_key.0,
+            partial_digest: partial_key.1,
+            digest: Some(digest),
+            processed: false,
+        });
     }
     if new_files.is_empty() {
         remove_file_if_exists(&process_metadata_json_path)?;
     } else {
-        write_json_file(&new_files, &process_metadata_json_path)?;
+        let process_metadata = ProcessMetadata {
+            digest_algorithm: algorithm,
+            new_files,
+        };
+        write_json_file_atomically(&process_metadata, &process_metadata_json_path)?;
     }
     Ok(())
 }
 
 fn main() -> Result<(), Box<String>> {
     let opt = get_opt();
+    let settings = PromptSettings {
+        assume: if opt.assume_yes {
+            Assume::Yes
+        } else if opt.assume_no {
+            Assume::No
+        } else {
+            Assume::Ask
+        },
+        quiet: opt.report.is_some(),
+    };
+    let mut events: Vec<Event> = Vec::new();
     let archive_metadata_path = opt.archive.join(ARCHIVE_METADATA_JSON);
     let mut archive_metadata: ArchiveMetadata = read_json_file(&archive_metadata_path, false)?;
+    let digest_algorithm = opt.digest.unwrap_or(archive_metadata.digest_algorithm);
+    if digest_algorithm != archive_metadata.digest_algorithm {
+        rehash_archive_metadata(&mut archive_metadata, &opt.archive, digest_algorithm)?;
+    }
+    let archive_ignore_patterns = load_ignore_patterns(&opt.archive, &opt.ignore)?;
     let mut actual_records: Vec<FileRecord> = Vec::new();
-    walk_dir(&opt.archive, &mut actual_records)?;
+    walk_dir(
+        &opt.archive,
+        &archive_ignore_patterns,
+        digest_algorithm,
+        &mut actual_records,
+    )?;
     let all_ok = check_for_existing_and_changed_files(
         &archive_metadata.expected,
         &mut actual_records,
         &opt.archive,
-    )? & check_for_renamed_files(&archive_metadata.expected, &mut actual_records)?
-        & check_for_deleted_files(&mut archive_metadata)?;
-    check_for_new_files(&mut archive_metadata, &mut actual_records);
-    if all_ok {
+        &settings,
+        &mut events,
+    )? & check_for_renamed_files(
+        &archive_metadata.expected,
+        &mut actual_records,
+        &settings,
+        &mut events,
+    )? & check_for_deleted_files(&mut archive_metadata, &settings, &mut events)?;
+    check_for_new_files(&mut archive_metadata, &mut actual_records, &mut events);
+    if all_ok && !settings.quiet {
         println!("Verified {}", opt.archive.to_string_lossy());
     }
     if let Some(process_dir) = opt.process {
-        process_files(&mut archive_metadata, &opt.archive, &process_dir)?;
+        let process_ignore_patterns = load_ignore_patterns(&process_dir, &opt.ignore)?;
+        process_files(
+            &mut archive_metadata,
+            &opt.archive,
+            &process_dir,
+            &process_ignore_patterns,
+            digest_algorithm,
+            &settings,
+            &mut events,
+        )?;
     }
     write_archive_metadata(&archive_metadata_path, &archive_metadata)?;
+    if let Some(report_path) = &opt.report {
+        write_json_file(&events, report_path)?;
+    }
     Ok(())
 }
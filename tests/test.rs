@@ -136,7 +136,7 @@ fn test_new_file() {
     std::fs::write(
         dir.child(ARCHIVE_METADATA_JSON),
         r#"{"expected":[
-        {"path":"file1","mtime":1321038671,"digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
+        {"path":"file1","mtime":1321038671,"size":9,"partial_digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f","digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
         ],"deleted":[]}"#,
     )
     .unwrap();
@@ -152,7 +152,7 @@ fn test_new_file() {
     assert_that!(
         &std::fs::read_to_string(dir.child(ARCHIVE_METADATA_JSON)).unwrap(),
         predicates::str::diff(
-            r#"{"expected":[{"path":"file1","mtime":1321038671,"digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"},{"path":"file2","mtime":1625166000,"digest":"869ed4d9645d8f65f6650ff3e987e335183c02ebed99deccea2917c6fd7be006"}],"deleted":[]}"#
+            r#"{"digest_algorithm":"sha256","expected":[{"path":"file1","mtime":1321038671,"size":9,"partial_digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f","digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"},{"path":"file2","mtime":1625166000,"size":9,"partial_digest":"869ed4d9645d8f65f6650ff3e987e335183c02ebed99deccea2917c6fd7be006","digest":"869ed4d9645d8f65f6650ff3e987e335183c02ebed99deccea2917c6fd7be006"}],"deleted":[]}"#
         )
     );
 }
@@ -164,7 +164,7 @@ fn test_contents_changed() {
     std::fs::write(
         dir.child(ARCHIVE_METADATA_JSON),
         r#"{"expected":[
-        {"path":"file1","mtime":1321038671,"digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
+        {"path":"file1","mtime":1321038671,"size":9,"partial_digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f","digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
         ],"deleted":[]}"#,
     )
         .unwrap();
@@ -215,7 +215,7 @@ fn test_accept_mtime_change() {
     std::fs::write(
         dir.child(ARCHIVE_METADATA_JSON),
         r#"{"expected":[
-        {"path":"file1","mtime":1321038671,"digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
+        {"path":"file1","mtime":1321038671,"size":9,"partial_digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f","digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
         ],"deleted":[]}"#,
     )
         .unwrap();
@@ -261,7 +261,7 @@ fn test_revert_mtime_change() {
     std::fs::write(
         dir.child(ARCHIVE_METADATA_JSON),
         r#"{"expected":[
-        {"path":"file1","mtime":1321038671,"digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
+        {"path":"file1","mtime":1321038671,"size":9,"partial_digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f","digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
         ],"deleted":[]}"#,
     )
         .unwrap();
@@ -307,7 +307,7 @@ fn test_renamed() {
     std::fs::write(
         dir.child(ARCHIVE_METADATA_JSON),
         r#"{"expected":[
-        {"path":"file1","mtime":1321038671,"digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
+        {"path":"file1","mtime":1321038671,"size":9,"partial_digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f","digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
         ],"deleted":[]}"#,
     )
         .unwrap();
@@ -352,8 +352,8 @@ fn test_deleted() {
     std::fs::write(
         dir.child(ARCHIVE_METADATA_JSON),
         r#"{"expected":[
-        {"path":"file1","mtime":1321038671,"digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"},
-        {"path":"file2","mtime":1321038671,"digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
+        {"path":"file1","mtime":1321038671,"size":9,"partial_digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f","digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"},
+        {"path":"file2","mtime":1321038671,"size":9,"partial_digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f","digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
         ],"deleted":[]}"#,
     )
         .unwrap();
@@ -379,7 +379,7 @@ fn test_deleted() {
     assert_that!(
         &std::fs::read_to_string(dir.child(ARCHIVE_METADATA_JSON)).unwrap(),
         predicates::str::diff(
-            r#"{"expected":[{"path":"file1","mtime":1321038671,"digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}],"deleted":[{"path":"file2","mtime":1321038671,"digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}]}"#
+            r#"{"digest_algorithm":"sha256","expected":[{"path":"file1","mtime":1321038671,"size":9,"partial_digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f","digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}],"deleted":[{"path":"file2","mtime":1321038671,"size":9,"partial_digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f","digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}]}"#
         )
     );
     Command::cargo_bin(BIN_NAME)
@@ -393,6 +393,147 @@ fn test_deleted() {
         )));
 }
 
+#[test]
+fn test_process_new_file_skips_full_digest() {
+    let archive = TempDir::new().unwrap();
+    write_file(archive.child("file1"), "contents1", TIME1);
+    std::fs::write(archive.child(ARCHIVE_METADATA_JSON), "").unwrap();
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", archive.path().to_string_lossy()))
+        .assert()
+        .success();
+
+    let process = TempDir::new().unwrap();
+    write_file(process.child("file2"), "contents2", TIME2);
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", archive.path().to_string_lossy()))
+        .arg(format!("--process={}", process.path().to_string_lossy()))
+        .assert()
+        .success()
+        .stdout(predicates::str::diff(format!(
+            "Verified {}\n",
+            archive.path().to_string_lossy()
+        )));
+    // file2's size and first block don't match anything in the archive, so it was recorded
+    // without ever computing a full digest of its contents.
+    let process_metadata = std::fs::read_to_string(process.child(PROCESS_METADATA_JSON)).unwrap();
+    assert!(process_metadata.contains("partial_digest"));
+    assert!(!process_metadata.contains("\"digest\""));
+}
+
+#[test]
+fn test_deduposaurignore_file_in_archive() {
+    let dir = TempDir::new().unwrap();
+    write_file(dir.child("file1"), "contents1", TIME1);
+    write_file(dir.child("ignoreme.log"), "contents2", TIME2);
+    std::fs::write(dir.child(".deduposaurignore"), "# comment\nignoreme.log\n").unwrap();
+    std::fs::write(dir.child(ARCHIVE_METADATA_JSON), "").unwrap();
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", dir.path().to_string_lossy()))
+        .assert()
+        .success()
+        .stdout(predicates::str::diff(format!(
+            "Verified {}\n",
+            dir.path().to_string_lossy()
+        )));
+    let archive_metadata = std::fs::read_to_string(dir.child(ARCHIVE_METADATA_JSON)).unwrap();
+    assert!(archive_metadata.contains("\"file1\""));
+    assert!(!archive_metadata.contains("ignoreme.log"));
+}
+
+#[test]
+fn test_ignore_flag_in_process_dir() {
+    let archive = TempDir::new().unwrap();
+    std::fs::write(archive.child(ARCHIVE_METADATA_JSON), "").unwrap();
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", archive.path().to_string_lossy()))
+        .assert()
+        .success();
+
+    let process = TempDir::new().unwrap();
+    write_file(process.child("file1"), "contents1", TIME1);
+    write_file(process.child("ignoreme.tmp"), "contents2", TIME2);
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", archive.path().to_string_lossy()))
+        .arg(format!("--process={}", process.path().to_string_lossy()))
+        .arg("--ignore=ignoreme.tmp")
+        .assert()
+        .success()
+        .stdout(predicates::str::diff(format!(
+            "Verified {}\n",
+            archive.path().to_string_lossy()
+        )));
+    let process_metadata = std::fs::read_to_string(process.child(PROCESS_METADATA_JSON)).unwrap();
+    assert!(process_metadata.contains("\"file1\""));
+    assert!(!process_metadata.contains("ignoreme.tmp"));
+    assert!(process.child("ignoreme.tmp").exists());
+}
+
+#[test]
+fn test_assume_yes_and_assume_no_conflict() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.child(ARCHIVE_METADATA_JSON), "").unwrap();
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", dir.path().to_string_lossy()))
+        .arg("--assume-yes")
+        .arg("--assume-no")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "expected only one of '--assume-yes' and '--assume-no'",
+        ));
+}
+
+#[test]
+fn test_assume_yes_accepts_mtime_change_without_stdin() {
+    let dir = TempDir::new().unwrap();
+    let file1 = write_file(dir.child("file1"), "contents1", TIME2);
+    std::fs::write(
+        dir.child(ARCHIVE_METADATA_JSON),
+        r#"{"expected":[
+        {"path":"file1","mtime":1321038671,"size":9,"partial_digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f","digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
+        ],"deleted":[]}"#,
+    )
+    .unwrap();
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", dir.path().to_string_lossy()))
+        .arg("--assume-yes")
+        .assert()
+        .success()
+        .stdout(predicates::str::diff(format!(
+            "WARNING file1 mtime changed 2011-11-11T11:11:11-08:00 -> 2021-07-01T12:00:00-07:00\nVerified {}\n",
+            dir.path().to_string_lossy()
+        )));
+    assert_eq!(get_mtime(&file1), TIME2);
+}
+
+#[test]
+fn test_report_writes_json_events_instead_of_stdout() {
+    let dir = TempDir::new().unwrap();
+    write_file(dir.child("file1"), "contents1", TIME1);
+    write_file(dir.child("file2"), "contents2", TIME2);
+    std::fs::write(dir.child(ARCHIVE_METADATA_JSON), "").unwrap();
+    let report_path = dir.child("report.json");
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", dir.path().to_string_lossy()))
+        .arg(format!("--report={}", report_path.to_string_lossy()))
+        .assert()
+        .success()
+        .stdout(predicates::str::diff(""));
+    let report = std::fs::read_to_string(&report_path).unwrap();
+    assert!(report.contains(r#""event":"new""#));
+    assert!(report.contains("\"file1\""));
+    assert!(report.contains("\"file2\""));
+}
+
 fn list_metadata_backups(dir: impl AsRef<Path>) -> Vec<String> {
     dir.as_ref()
         .read_dir()
@@ -431,7 +572,7 @@ fn test_metadata_json_file_backups() {
         )));
     assert_that!(
         &std::fs::read_to_string(dir.child(ARCHIVE_METADATA_JSON)).unwrap(),
-        predicates::str::diff(r#"{"expected":[],"deleted":[]}"#)
+        predicates::str::diff(r#"{"digest_algorithm":"sip128","expected":[],"deleted":[]}"#)
     );
     let metadata_backups = list_metadata_backups(dir.path());
     assert_eq!(1, metadata_backups.len());
@@ -456,7 +597,7 @@ fn test_metadata_json_file_backups() {
         assert_that!(
             &std::fs::read_to_string(dir.child(ARCHIVE_METADATA_JSON)).unwrap(),
             predicates::str::diff(
-                r#"{"expected":[{"path":"file1","mtime":1321038671,"digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}],"deleted":[]}"#
+                r#"{"digest_algorithm":"sip128","expected":[{"path":"file1","mtime":1321038671,"size":9,"partial_digest":"bc4348b7d629eb1fd7116d8d7df99d0c","digest":"bc4348b7d629eb1fd7116d8d7df99d0c"}],"deleted":[]}"#
             )
         );
         let metadata_backups = list_metadata_backups(dir.path());
@@ -473,7 +614,7 @@ fn test_metadata_json_file_backups() {
         );
         assert_that!(
             &std::fs::read_to_string(dir.child(second_backup)).unwrap(),
-            predicates::str::diff(r#"{"expected":[],"deleted":[]}"#)
+            predicates::str::diff(r#"{"digest_algorithm":"sip128","expected":[],"deleted":[]}"#)
         );
     }
 }
@@ -523,6 +664,104 @@ fn test_renames_dupe() {
     check();
 }
 
+#[test]
+fn test_new_archive_defaults_to_sip128_digest() {
+    let dir = TempDir::new().unwrap();
+    write_file(dir.child("file1"), "contents1", TIME1);
+    std::fs::write(dir.child(ARCHIVE_METADATA_JSON), "").unwrap();
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", dir.path().to_string_lossy()))
+        .assert()
+        .success()
+        .stdout(predicates::str::diff(format!(
+            "Verified {}\n",
+            dir.path().to_string_lossy()
+        )));
+    assert_that!(
+        &std::fs::read_to_string(dir.child(ARCHIVE_METADATA_JSON)).unwrap(),
+        predicates::str::diff(
+            r#"{"digest_algorithm":"sip128","expected":[{"path":"file1","mtime":1321038671,"size":9,"partial_digest":"bc4348b7d629eb1fd7116d8d7df99d0c","digest":"bc4348b7d629eb1fd7116d8d7df99d0c"}],"deleted":[]}"#
+        )
+    );
+}
+
+#[test]
+fn test_digest_flag_rejects_unknown_algorithm() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.child(ARCHIVE_METADATA_JSON), "").unwrap();
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", dir.path().to_string_lossy()))
+        .arg("--digest=md5")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "unknown digest algorithm \"md5\"",
+        ));
+}
+
+#[test]
+fn test_digest_flag_rehashes_existing_archive() {
+    let dir = TempDir::new().unwrap();
+    write_file(dir.child("file1"), "contents1", TIME1);
+    std::fs::write(
+        dir.child(ARCHIVE_METADATA_JSON),
+        r#"{"expected":[
+        {"path":"file1","mtime":1321038671,"size":9,"partial_digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f","digest":"809da78733fb34d7548ff1a8abe962ec865f8db07820e00f7a61ba79e2b6ff9f"}
+        ],"deleted":[]}"#,
+    )
+    .unwrap();
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", dir.path().to_string_lossy()))
+        .arg("--digest=blake3")
+        .assert()
+        .success()
+        .stdout(predicates::str::diff(format!(
+            "Verified {}\n",
+            dir.path().to_string_lossy()
+        )));
+    assert_that!(
+        &std::fs::read_to_string(dir.child(ARCHIVE_METADATA_JSON)).unwrap(),
+        predicates::str::diff(
+            r#"{"digest_algorithm":"blake3","expected":[{"path":"file1","mtime":1321038671,"size":9,"partial_digest":"1bcc0d4c4fb690413c2b6b784e65229fb0ba4362abf6c2e92e8cb2906c50bd63","digest":"1bcc0d4c4fb690413c2b6b784e65229fb0ba4362abf6c2e92e8cb2906c50bd63"}],"deleted":[]}"#
+        )
+    );
+}
+
+#[test]
+fn test_digest_flag_does_not_orphan_pending_process_file() {
+    let archive = TempDir::new().unwrap();
+    std::fs::write(archive.child(ARCHIVE_METADATA_JSON), "").unwrap();
+    let process = TempDir::new().unwrap();
+    write_file(process.child("file1"), "contents1", TIME1);
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", archive.path().to_string_lossy()))
+        .arg(format!("--process={}", process.path().to_string_lossy()))
+        .assert()
+        .success();
+    let process_metadata_before =
+        std::fs::read_to_string(process.child(PROCESS_METADATA_JSON)).unwrap();
+    assert!(process_metadata_before.contains("\"digest_algorithm\":\"sip128\""));
+    // Switching --digest must re-hash the still-pending file1 record too, not just the
+    // archive's, or its now-stale PartialKey would make it look deleted from the process dir.
+    Command::cargo_bin(BIN_NAME)
+        .unwrap()
+        .arg(format!("--archive={}", archive.path().to_string_lossy()))
+        .arg(format!("--process={}", process.path().to_string_lossy()))
+        .arg("--digest=blake3")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("was deleted").not());
+    assert!(process.child("file1").exists());
+    let process_metadata_after =
+        std::fs::read_to_string(process.child(PROCESS_METADATA_JSON)).unwrap();
+    assert!(process_metadata_after.contains("\"digest_algorithm\":\"blake3\""));
+    assert!(process_metadata_after.contains("\"file1\""));
+}
+
 #[test]
 fn test_renames_deleted() {
     let archive = TempDir::new().unwrap();